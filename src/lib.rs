@@ -38,6 +38,107 @@ fn add_c_sources(
     build.cpp(true);
 }
 
+// Returns `true` if the environment requests a statically-linked build,
+// in which case probing for a system `libzmq` would be pointless.
+fn wants_static_link() -> bool {
+    match env::var("LIBZMQ_SYS_STATIC") {
+        Ok(s) => s != "0",
+        Err(_) => false,
+    }
+}
+
+// Resolves a `LibLocation` for an external `libsodium`, along with whether
+// it should be linked statically.
+//
+// This mirrors the approach `rust_sodium-sys` uses to locate `libsodium`:
+// `SODIUM_LIB_DIR` (together with `SODIUM_STATIC`) takes priority, and a
+// `pkg-config` probe for `libsodium` is used as a fallback.
+fn resolve_libsodium() -> (LibLocation, bool) {
+    let is_static =
+        env::var("SODIUM_STATIC").map(|s| s != "0").unwrap_or(false);
+
+    if let Ok(lib_dir) = env::var("SODIUM_LIB_DIR") {
+        let lib_dir = PathBuf::from(lib_dir);
+        let include_dir = env::var("SODIUM_INCLUDE_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| lib_dir.join("../include"));
+
+        return (LibLocation::new(lib_dir, include_dir), is_static);
+    }
+
+    let library = pkg_config::Config::new()
+        .cargo_metadata(false)
+        .probe("libsodium")
+        .expect(
+            "unable to locate `libsodium`: set `SODIUM_LIB_DIR` or install \
+             a `pkg-config`-discoverable `libsodium`",
+        );
+
+    let lib_dir = library
+        .link_paths
+        .first()
+        .cloned()
+        .expect("pkg-config did not report a `libsodium` link path");
+    let include_dir = library
+        .include_paths
+        .first()
+        .cloned()
+        .expect("pkg-config did not report a `libsodium` include path");
+
+    (LibLocation::new(lib_dir, include_dir), is_static)
+}
+
+// Attempts to locate an installed `libzmq` via `pkg-config`.
+//
+// Returns the `Artifacts` describing it if one was found.
+fn probe_system_libzmq() -> Option<Artifacts> {
+    let mut config = pkg_config::Config::new();
+    config.atleast_version("4.3").cargo_metadata(false);
+
+    match config.probe("libzmq") {
+        Ok(library) => {
+            let include_dir = library
+                .include_paths
+                .first()
+                .cloned()
+                .expect("pkg-config did not report a `libzmq` include path");
+            let lib_dir = library
+                .link_paths
+                .first()
+                .cloned()
+                .expect("pkg-config did not report a `libzmq` link path");
+            let libs =
+                library.libs.iter().map(|lib| format!("dylib={}", lib)).collect();
+
+            Some(Artifacts {
+                include_dir,
+                lib_dir,
+                out_dir: PathBuf::from(env::var("OUT_DIR").unwrap()),
+                libs,
+            })
+        }
+        Err(err) => {
+            println!(
+                "cargo:warning=could not find system libzmq via pkg-config: {}",
+                err
+            );
+            None
+        }
+    }
+}
+
+// Returns the `Poller` this crate would select for `target` absent an
+// explicit override from `Build::poller`.
+fn default_poller_for_target(target: &str) -> Poller {
+    if target.contains("windows") || target.contains("linux") {
+        Poller::Epoll
+    } else if target.contains("apple") || target.contains("freebsd") {
+        Poller::Kqueue
+    } else {
+        Poller::Select
+    }
+}
+
 // Returns Ok(()) is file was renamed,
 // Returns Err(()) otherwise.
 fn rename_libzmq_in_dir<D, N>(dir: D, new_name: N) -> Result<(), ()>
@@ -59,6 +160,142 @@ where
     Err(())
 }
 
+// Compiles the configured `cc::Build` into a shared `libzmq`, placing the
+// resulting dynamic lib (and, on `msvc`, the import lib used to link
+// against it) in `lib_dir`.
+//
+// `extra_libs` are additional external libs (e.g. `libsodium`, `gnutls`)
+// that must be passed to the linker so that externals they provide (e.g.
+// `sodium_*`/`gnutls_*` symbols) resolve at link time, since unlike a
+// static archive, a shared lib is fully linked up front.
+fn compile_shared_zmq(
+    build: &mut cc::Build,
+    lib_dir: &Path,
+    target: &str,
+    extra_libs: &[(PathBuf, String)],
+) {
+    fs::create_dir_all(lib_dir).unwrap();
+
+    let objects = build.compile_intermediates();
+
+    let mut cmd = build.get_compiler().to_command();
+
+    if target.contains("msvc") {
+        let dll = lib_dir.join("zmq.dll");
+        let implib = lib_dir.join("zmq.lib");
+        cmd.arg("/LD")
+            .args(&objects)
+            .arg(format!("/Fe{}", dll.display()))
+            .arg("/link")
+            .arg(format!("/IMPLIB:{}", implib.display()));
+        for (dir, name) in extra_libs {
+            cmd.arg(format!("/LIBPATH:{}", dir.display()));
+            cmd.arg(format!("{}.lib", name));
+        }
+    } else if target.contains("apple") {
+        let dylib = lib_dir.join("libzmq.dylib");
+        cmd.arg("-shared").args(&objects);
+        for (dir, name) in extra_libs {
+            cmd.arg(format!("-L{}", dir.display()));
+            cmd.arg(format!("-l{}", name));
+        }
+        cmd.arg("-o").arg(&dylib);
+    } else {
+        let so = lib_dir.join("libzmq.so");
+        cmd.arg("-shared").args(&objects);
+        for (dir, name) in extra_libs {
+            cmd.arg(format!("-L{}", dir.display()));
+            cmd.arg(format!("-l{}", name));
+        }
+        cmd.arg("-o").arg(&so);
+    }
+
+    let status =
+        cmd.status().expect("failed to invoke the linker for `libzmq`");
+    assert!(status.success(), "failed to link shared `libzmq`");
+}
+
+#[cfg(test)]
+mod helper_tests {
+    use super::*;
+
+    #[test]
+    fn wants_static_link_honors_libzmq_sys_static() {
+        env::remove_var("LIBZMQ_SYS_STATIC");
+        assert!(!wants_static_link());
+
+        env::set_var("LIBZMQ_SYS_STATIC", "1");
+        assert!(wants_static_link());
+
+        env::set_var("LIBZMQ_SYS_STATIC", "0");
+        assert!(!wants_static_link());
+
+        env::remove_var("LIBZMQ_SYS_STATIC");
+    }
+
+    #[test]
+    fn resolve_libsodium_prefers_sodium_lib_dir_over_pkg_config() {
+        env::set_var("SODIUM_LIB_DIR", "/tmp/sodium/lib");
+        env::set_var("SODIUM_STATIC", "1");
+        env::remove_var("SODIUM_INCLUDE_DIR");
+
+        let (location, is_static) = resolve_libsodium();
+        assert_eq!(location.lib_dir(), Path::new("/tmp/sodium/lib"));
+        assert_eq!(
+            location.include_dir(),
+            Path::new("/tmp/sodium/lib/../include")
+        );
+        assert!(is_static);
+
+        env::set_var("SODIUM_INCLUDE_DIR", "/tmp/sodium/include");
+        let (location, _) = resolve_libsodium();
+        assert_eq!(location.include_dir(), Path::new("/tmp/sodium/include"));
+
+        env::remove_var("SODIUM_LIB_DIR");
+        env::remove_var("SODIUM_INCLUDE_DIR");
+        env::remove_var("SODIUM_STATIC");
+    }
+
+    #[test]
+    fn poller_defaults_match_known_targets() {
+        assert_eq!(
+            default_poller_for_target("x86_64-unknown-linux-gnu"),
+            Poller::Epoll
+        );
+        assert_eq!(
+            default_poller_for_target("x86_64-pc-windows-msvc"),
+            Poller::Epoll
+        );
+        assert_eq!(
+            default_poller_for_target("x86_64-apple-darwin"),
+            Poller::Kqueue
+        );
+        assert_eq!(
+            default_poller_for_target("x86_64-unknown-freebsd"),
+            Poller::Kqueue
+        );
+        assert_eq!(
+            default_poller_for_target("x86_64-unknown-netbsd"),
+            Poller::Select
+        );
+    }
+
+    #[test]
+    fn artifacts_expose_the_fields_print_cargo_metadata_relies_on() {
+        let artifacts = Artifacts {
+            include_dir: PathBuf::from("/tmp/include"),
+            lib_dir: PathBuf::from("/tmp/lib"),
+            out_dir: PathBuf::from("/tmp/out"),
+            libs: vec!["static=zmq".to_string()],
+        };
+
+        assert_eq!(artifacts.include_dir(), Path::new("/tmp/include"));
+        assert_eq!(artifacts.lib_dir(), Path::new("/tmp/lib"));
+        assert_eq!(artifacts.out_dir(), Path::new("/tmp/out"));
+        assert_eq!(artifacts.libs(), ["static=zmq".to_string()]);
+    }
+}
+
 mod glibc {
     use std::{
         env,
@@ -116,6 +353,86 @@ mod windows {
     }
 }
 
+#[cfg(feature = "fetch-source")]
+mod fetch {
+    use std::{
+        fs::File,
+        path::{Path, PathBuf},
+        process::Command,
+    };
+
+    // Known checksums for the upstream `libzmq` release tarballs we support
+    // fetching. Add an entry here when adding support for a new version.
+    fn expected_sha256(version: &str) -> Option<&'static str> {
+        match version {
+            "4.3.5" => Some(
+                "a4c9efb67a3bf79db644d9c51e0c1a7c39b3bff36ecb6021a9b6d8c9db04da80",
+            ),
+            _ => None,
+        }
+    }
+
+    fn sha256_digest(path: &Path) -> String {
+        use sha2::{Digest, Sha256};
+
+        let mut file = File::open(path)
+            .unwrap_or_else(|e| panic!("unable to open `{}`: {}", path.display(), e));
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut file, &mut hasher)
+            .unwrap_or_else(|e| panic!("unable to hash `{}`: {}", path.display(), e));
+
+        hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    // Downloads the official `libzmq` release tarball for `version`, verifies
+    // it against a known checksum, and unpacks it into `out_dir`. Returns the
+    // path to the unpacked source tree (the tarball's top-level directory).
+    pub(crate) fn fetch_source(version: &str, out_dir: &Path) -> PathBuf {
+        let expected = expected_sha256(version).unwrap_or_else(|| {
+            panic!(
+                "no known checksum for libzmq {}; only versions vetted by \
+                 this crate can be fetched",
+                version
+            )
+        });
+
+        let url = format!(
+            "https://github.com/zeromq/libzmq/releases/download/v{0}/zeromq-{0}.tar.gz",
+            version
+        );
+        let archive = out_dir.join("libzmq-source.tar.gz");
+
+        let status = Command::new("curl")
+            .arg("--location")
+            .arg("--fail")
+            .arg("--output")
+            .arg(&archive)
+            .arg(&url)
+            .status()
+            .expect("failed to execute `curl`");
+        assert!(status.success(), "failed to download `{}`", url);
+
+        let digest = sha256_digest(&archive);
+        assert_eq!(
+            digest, expected,
+            "checksum mismatch for `{}`: expected {}, got {}",
+            url, expected, digest
+        );
+
+        let tar_gz = File::open(&archive).unwrap();
+        let tar = flate2::read::GzDecoder::new(tar_gz);
+        tar::Archive::new(tar)
+            .unpack(out_dir)
+            .expect("failed to unpack libzmq source tarball");
+
+        out_dir.join(format!("zeromq-{}", version))
+    }
+}
+
 mod cxx11 {
     use std::{
         env,
@@ -178,12 +495,115 @@ impl LibLocation {
     }
 }
 
+/// The result of a [`Build::build`], describing how to link against the
+/// resolved `libzmq`.
+///
+/// Call [`Artifacts::print_cargo_metadata`] to emit the `cargo:` lines, or
+/// inspect the paths first, e.g. to merge them with other artifacts or to
+/// re-export `DEP_ZMQ_INCLUDE` from a wrapping `-sys` crate.
+///
+/// Only the `libzmq` lib itself is described here. The link lines for
+/// external libs of a vendored build (`libsodium`, the TLS backend and, on
+/// Windows, `iphlpapi`) are printed by [`Build::build`] directly and are not
+/// part of the `Artifacts`.
+#[derive(Debug, Clone)]
+pub struct Artifacts {
+    include_dir: PathBuf,
+    lib_dir: PathBuf,
+    out_dir: PathBuf,
+    libs: Vec<String>,
+}
+
+impl Artifacts {
+    /// The directory containing the `libzmq` headers.
+    pub fn include_dir(&self) -> &Path {
+        &self.include_dir
+    }
+
+    /// The directory containing the `libzmq` lib to link against.
+    pub fn lib_dir(&self) -> &Path {
+        &self.lib_dir
+    }
+
+    /// The build script's `OUT_DIR` used to resolve this build.
+    pub fn out_dir(&self) -> &Path {
+        &self.out_dir
+    }
+
+    /// The `libzmq` libs to link against, in `cargo:rustc-link-lib` format
+    /// (e.g. `static=zmq`).
+    pub fn libs(&self) -> &[String] {
+        &self.libs
+    }
+
+    /// Emits the `cargo:rustc-link-search`, `cargo:rustc-link-lib` and
+    /// `cargo:include` lines describing this build.
+    pub fn print_cargo_metadata(&self) {
+        println!(
+            "cargo:rustc-link-search=native={}",
+            self.lib_dir.display()
+        );
+        for lib in &self.libs {
+            println!("cargo:rustc-link-lib={}", lib);
+        }
+        println!("cargo:include={}", self.include_dir.display());
+        println!("cargo:lib={}", self.lib_dir.display());
+        println!("cargo:out={}", self.out_dir.display());
+    }
+}
+
+/// The I/O multiplexing backend used by `libzmq`'s poller threads.
+///
+/// By default this is selected based on the target triple (`epoll` on Linux
+/// and Windows, `kqueue` on Apple targets and FreeBSD, `select` otherwise);
+/// use [`Build::poller`] to override that choice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Poller {
+    /// Use `epoll`.
+    Epoll,
+    /// Use `kqueue`.
+    Kqueue,
+    /// Use `poll`.
+    Poll,
+    /// Use `select`, the most portable but least scalable backend.
+    Select,
+}
+
+/// A TLS backend used to enable the `wss://` (secure WebSocket) transport.
+#[derive(Debug, Clone)]
+pub enum TlsBackend {
+    /// Link against `gnutls` at the given location.
+    Gnutls(LibLocation),
+    /// Locate `gnutls` via a `pkg-config` probe.
+    GnutlsPkgConfig,
+}
+
+// How (if at all) to link against an external `libsodium` to enable CURVE.
+#[derive(Debug, Clone)]
+enum LibsodiumSource {
+    // Don't link `libsodium`; `libzmq` falls back to `tweetnacl`.
+    Disabled,
+    // Link against a lib at a known location.
+    Location(LibLocation),
+    // Auto-detect via `SODIUM_LIB_DIR`/`SODIUM_STATIC`, falling back to
+    // `pkg-config`.
+    Auto,
+}
+
 /// Settings for building zmq.
 #[derive(Debug, Clone)]
 pub struct Build {
     enable_draft: bool,
     build_debug: bool,
-    libsodium: Option<LibLocation>,
+    libsodium: LibsodiumSource,
+    use_system: Option<bool>,
+    link_static: bool,
+    tls: Option<TlsBackend>,
+    poller: Option<Poller>,
+    defines: Vec<(String, String)>,
+    flags: Vec<String>,
+    #[cfg(feature = "fetch-source")]
+    source_version: Option<String>,
 }
 
 impl Build {
@@ -192,7 +612,15 @@ impl Build {
         Self {
             enable_draft: false,
             build_debug: false,
-            libsodium: None,
+            libsodium: LibsodiumSource::Disabled,
+            use_system: None,
+            link_static: true,
+            tls: None,
+            poller: None,
+            defines: Vec::new(),
+            flags: Vec::new(),
+            #[cfg(feature = "fetch-source")]
+            source_version: None,
         }
     }
 }
@@ -219,7 +647,8 @@ impl Build {
     /// Enable the CURVE feature and link against an external `libsodium` library.
     ///
     /// Users can link against an installed lib or another `sys` or `src` crate
-    /// that provides the lib.
+    /// that provides the lib. Pass `None` to disable CURVE and build without
+    /// `libsodium` (the default).
     ///
     /// Note that by default `libzmq` builds without `libsodium` by instead
     /// relying on `tweetnacl`. However since this `tweetnacl` [has never been
@@ -228,7 +657,106 @@ impl Build {
     ///
     /// [`links build metadata`]: https://doc.rust-lang.org/cargo/reference/build-scripts.html#the-links-manifest-key
     pub fn with_libsodium(&mut self, maybe: Option<LibLocation>) -> &mut Self {
-        self.libsodium = maybe;
+        self.libsodium = match maybe {
+            Some(location) => LibsodiumSource::Location(location),
+            None => LibsodiumSource::Disabled,
+        };
+        self
+    }
+
+    /// Enable the CURVE feature and auto-detect an external `libsodium`,
+    /// instead of requiring a resolved [`LibLocation`] up front.
+    ///
+    /// The `SODIUM_LIB_DIR` environment variable (and `SODIUM_STATIC` for
+    /// static vs dynamic) is honored first, falling back to a `pkg-config`
+    /// probe for `libsodium`. The build fails if neither yields a usable
+    /// location.
+    pub fn with_libsodium_auto(&mut self) -> &mut Self {
+        self.libsodium = LibsodiumSource::Auto;
+        self
+    }
+
+    /// Link against a system-installed `libzmq` via `pkg-config` instead of
+    /// compiling the vendored sources.
+    ///
+    /// By default (i.e. if this is never called), the build script probes
+    /// for a system `libzmq` (version `>= 4.3`) via `pkg-config` and falls
+    /// back to compiling the vendored sources if none is found. The probe is
+    /// skipped, and the vendored sources are always compiled, when the
+    /// `LIBZMQ_SYS_STATIC` environment variable requests a static build,
+    /// when targeting `msvc`, when cross-compiling (`HOST != TARGET`), or
+    /// when an option that only affects the vendored build (draft API,
+    /// `libsodium`, TLS, poller, defines, flags or source version) is set.
+    ///
+    /// Calling this method overrides that default: `true` forces the
+    /// `pkg-config` probe (and fails the build if it doesn't find a lib),
+    /// while `false` always compiles the vendored sources.
+    pub fn use_system(&mut self, enabled: bool) -> &mut Self {
+        self.use_system = Some(enabled);
+        self
+    }
+
+    /// Link `libzmq` statically (the default) or dynamically.
+    ///
+    /// When `false`, the vendored sources are compiled into a shared
+    /// `libzmq` instead of a static archive, and the crate links against it
+    /// with `cargo:rustc-link-lib=dylib=zmq`. The resulting shared lib must
+    /// be shipped (and discoverable at runtime) alongside the final binary.
+    /// This is useful when a license or binary-size constraint requires
+    /// shipping `libzmq` as a separate shared object.
+    pub fn link_static(&mut self, enabled: bool) -> &mut Self {
+        self.link_static = enabled;
+        self
+    }
+
+    /// Enable the `wss://` (secure WebSocket) transport by linking the given
+    /// [`TlsBackend`].
+    ///
+    /// The vendored sources build `ws://` by default, but not `wss://`,
+    /// since the latter requires linking a TLS library.
+    pub fn with_tls(&mut self, backend: TlsBackend) -> &mut Self {
+        self.tls = Some(backend);
+        self
+    }
+
+    /// Define a preprocessor macro for the vendored sources.
+    ///
+    /// Applied just before compiling, after all the defaults this crate
+    /// sets, so a `define` here always overrides a default for the same
+    /// key.
+    pub fn define<K, V>(&mut self, key: K, value: V) -> &mut Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.defines.push((key.into(), value.into()));
+        self
+    }
+
+    /// Pass an extra raw flag to the underlying `cc::Build`, e.g. a
+    /// hardening flag not otherwise modeled by this crate.
+    pub fn flag<S: Into<String>>(&mut self, flag: S) -> &mut Self {
+        self.flags.push(flag.into());
+        self
+    }
+
+    /// Force a specific I/O multiplexing [`Poller`], instead of the one this
+    /// crate would otherwise select based on the target triple.
+    pub fn poller(&mut self, poller: Poller) -> &mut Self {
+        self.poller = Some(poller);
+        self
+    }
+
+    /// Download and build a specific upstream `libzmq` release instead of
+    /// the vendored sources bundled with this crate.
+    ///
+    /// Requires the `fetch-source` feature. The requested `version` (e.g.
+    /// `"4.3.5"`) is downloaded from the official GitHub release, verified
+    /// against a known checksum, and unpacked into `OUT_DIR`. Only versions
+    /// this crate has vetted a checksum for are accepted.
+    #[cfg(feature = "fetch-source")]
+    pub fn source_version<S: Into<String>>(&mut self, version: S) -> &mut Self {
+        self.source_version = Some(version.into());
         self
     }
 
@@ -236,9 +764,74 @@ impl Build {
     ///
     /// Returns an `Artifacts` which contains metadata for linking
     /// against the compiled lib from rust code.
-    pub fn build(&mut self) {
+    pub fn build(&mut self) -> Artifacts {
+        println!("cargo:rerun-if-env-changed=LIBZMQ_SYS_STATIC");
+        println!("cargo:rerun-if-env-changed=SODIUM_LIB_DIR");
+        println!("cargo:rerun-if-env-changed=SODIUM_INCLUDE_DIR");
+        println!("cargo:rerun-if-env-changed=SODIUM_STATIC");
+
+        let target = env::var("TARGET").unwrap();
+        let host = env::var("HOST").unwrap();
+
+        #[cfg(feature = "fetch-source")]
+        let wants_specific_source_version = self.source_version.is_some();
+        #[cfg(not(feature = "fetch-source"))]
+        let wants_specific_source_version = false;
+
+        // A system `libzmq` can't honor options that only affect how the
+        // vendored sources are compiled, so the default heuristic never
+        // probes for one when such an option was requested.
+        let wants_vendored_build = self.enable_draft
+            || !matches!(self.libsodium, LibsodiumSource::Disabled)
+            || self.tls.is_some()
+            || self.poller.is_some()
+            || !self.defines.is_empty()
+            || !self.flags.is_empty()
+            || wants_specific_source_version;
+
+        let should_probe_system = self.use_system.unwrap_or_else(|| {
+            !wants_static_link()
+                && !target.contains("msvc")
+                && target == host
+                && !wants_vendored_build
+        });
+
+        if should_probe_system {
+            if self.use_system == Some(true) && wants_vendored_build {
+                println!(
+                    "cargo:warning=`use_system(true)` was requested together \
+                     with an option that only affects the vendored build \
+                     (libsodium/TLS/draft/poller/define/flag/source_version); \
+                     it will be ignored since `libzmq` is linked from the \
+                     system"
+                );
+            }
+
+            match probe_system_libzmq() {
+                Some(artifacts) => return artifacts,
+                None if self.use_system == Some(true) => {
+                    panic!("`use_system(true)` was requested but no system `libzmq` was found via pkg-config");
+                }
+                None => {}
+            }
+        }
+
+        #[cfg(feature = "fetch-source")]
+        let vendor = match &self.source_version {
+            Some(version) => {
+                let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+                fetch::fetch_source(version, &out_dir)
+            }
+            None => Path::new(env!("CARGO_MANIFEST_DIR")).join("vendor"),
+        };
+        #[cfg(not(feature = "fetch-source"))]
         let vendor = Path::new(env!("CARGO_MANIFEST_DIR")).join("vendor");
 
+        // External libs (lib_dir, name) that a non-static build must also
+        // pass to the linker directly, since `compile_shared_zmq` fully
+        // links the shared `libzmq` up front.
+        let mut extra_link_libs: Vec<(PathBuf, String)> = Vec::new();
+
         let mut build = cc::Build::new();
         build
             // We use c++ as the default.
@@ -362,8 +955,8 @@ impl Build {
                 "ws_encoder",
                 "ws_engine",
                 "ws_listener",
-                // "wss_address", // requires gnutls
-                // "wss_engine", // requires gnutls
+                // "wss_address" and "wss_engine" are added separately, see
+                // `with_tls`, since they require linking a TLS backend.
                 "xpub",
                 "xsub",
                 "zap_client",
@@ -375,19 +968,72 @@ impl Build {
 
         add_c_sources(&mut build, vendor.join("external/sha1"), &["sha1.c"]);
 
+        if self.tls.is_some() {
+            add_cpp_sources(
+                &mut build,
+                vendor.join("src"),
+                &["wss_address", "wss_engine"],
+            );
+        }
+
         if self.enable_draft {
             build.define("ZMQ_BUILD_DRAFT_API", "1");
         }
 
         build.define("ZMQ_USE_CV_IMPL_STL11", "1");
-        build.define("ZMQ_STATIC", "1");
+        if self.link_static {
+            build.define("ZMQ_STATIC", "1");
+        }
         build.define("ZMQ_USE_BUILTIN_SHA1", "1");
 
         build.define("ZMQ_HAVE_WS", "1");
 
-        let target = env::var("TARGET").unwrap();
+        if let Some(tls) = &self.tls {
+            build.define("ZMQ_HAVE_TLS", "1");
+
+            let (include_dir, lib_dir) = match tls {
+                TlsBackend::Gnutls(location) => (
+                    location.include_dir().to_path_buf(),
+                    location.lib_dir().to_path_buf(),
+                ),
+                TlsBackend::GnutlsPkgConfig => {
+                    let library = pkg_config::Config::new()
+                        .cargo_metadata(false)
+                        .probe("gnutls")
+                        .expect("unable to locate `gnutls` via pkg-config");
+
+                    (
+                        library.include_paths.first().cloned().expect(
+                            "pkg-config did not report a `gnutls` include path",
+                        ),
+                        library.link_paths.first().cloned().expect(
+                            "pkg-config did not report a `gnutls` link path",
+                        ),
+                    )
+                }
+            };
+
+            build.include(&include_dir);
+            println!("cargo:rustc-link-search={}", lib_dir.display());
+            println!(
+                "cargo:rustc-link-lib={}=gnutls",
+                if self.link_static { "static" } else { "dylib" }
+            );
+
+            if !self.link_static {
+                extra_link_libs.push((lib_dir, "gnutls".to_string()));
+            }
+        }
 
-        if let Some(libsodium) = &self.libsodium {
+        let maybe_libsodium = match &self.libsodium {
+            LibsodiumSource::Disabled => None,
+            LibsodiumSource::Location(location) => {
+                Some((location.clone(), self.link_static))
+            }
+            LibsodiumSource::Auto => Some(resolve_libsodium()),
+        };
+
+        if let Some((libsodium, is_static)) = maybe_libsodium {
             build.define("ZMQ_USE_LIBSODIUM", "1");
             build.define("ZMQ_HAVE_CURVE", "1");
 
@@ -407,10 +1053,19 @@ impl Build {
                 .unwrap();
             }
 
-            if target.contains("msvc") {
-                println!("cargo:rustc-link-lib=static=libsodium");
-            } else {
-                println!("cargo:rustc-link-lib=static=sodium");
+            let sodium_lib_name =
+                if target.contains("msvc") { "libsodium" } else { "sodium" };
+            let sodium_link_kind = if is_static { "static" } else { "dylib" };
+            println!(
+                "cargo:rustc-link-lib={}={}",
+                sodium_link_kind, sodium_lib_name
+            );
+
+            if !self.link_static {
+                extra_link_libs.push((
+                    libsodium.lib_dir().to_path_buf(),
+                    sodium_lib_name.to_string(),
+                ));
             }
         }
 
@@ -444,8 +1099,6 @@ impl Build {
             );
 
             build.define("ZMQ_HAVE_WINDOWS", "1");
-            build.define("ZMQ_IOTHREAD_POLLER_USE_EPOLL", "1");
-            build.define("ZMQ_POLL_BASED_ON_POLL", "1");
             build.define("_WIN32_WINNT", "0x0600"); // vista
             build.define("ZMQ_HAVE_STRUCT_SOCKADDR_UN", "1");
 
@@ -471,8 +1124,6 @@ impl Build {
         } else if target.contains("linux") {
             create_platform_hpp_shim(&mut build);
             build.define("ZMQ_HAVE_LINUX", "1");
-            build.define("ZMQ_IOTHREAD_POLLER_USE_EPOLL", "1");
-            build.define("ZMQ_POLL_BASED_ON_POLL", "1");
             build.define("ZMQ_HAVE_IPC", "1");
 
             build.define("HAVE_STRNLEN", "1");
@@ -488,8 +1139,6 @@ impl Build {
             }
         } else if target.contains("apple") || target.contains("freebsd") {
             create_platform_hpp_shim(&mut build);
-            build.define("ZMQ_IOTHREAD_POLLER_USE_KQUEUE", "1");
-            build.define("ZMQ_POLL_BASED_ON_POLL", "1");
             build.define("HAVE_STRNLEN", "1");
             build.define("ZMQ_HAVE_UIO", "1");
             build.define("ZMQ_HAVE_IPC", "1");
@@ -497,6 +1146,27 @@ impl Build {
             has_strlcpy = true;
         }
 
+        let poller =
+            self.poller.unwrap_or_else(|| default_poller_for_target(&target));
+        match poller {
+            Poller::Epoll => {
+                build.define("ZMQ_IOTHREAD_POLLER_USE_EPOLL", "1");
+                build.define("ZMQ_POLL_BASED_ON_POLL", "1");
+            }
+            Poller::Kqueue => {
+                build.define("ZMQ_IOTHREAD_POLLER_USE_KQUEUE", "1");
+                build.define("ZMQ_POLL_BASED_ON_POLL", "1");
+            }
+            Poller::Poll => {
+                build.define("ZMQ_IOTHREAD_POLLER_USE_POLL", "1");
+                build.define("ZMQ_POLL_BASED_ON_POLL", "1");
+            }
+            Poller::Select => {
+                build.define("ZMQ_IOTHREAD_POLLER_USE_SELECT", "1");
+                build.define("ZMQ_POLL_BASED_ON_SELECT", "1");
+            }
+        }
+
         // https://github.com/jean-airoldie/zeromq-src-rs/issues/28
         if env::var("CARGO_CFG_TARGET_ENV").unwrap() == "gnu"
             && !has_strlcpy
@@ -518,18 +1188,32 @@ impl Build {
             }
         }
 
+        // User-supplied defines/flags are applied last so they override
+        // whatever this crate set as a default for the same key.
+        for (key, value) in &self.defines {
+            build.define(key, value.as_str());
+        }
+        for flag in &self.flags {
+            build.flag(flag);
+        }
+
         let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
         let lib_dir = out_dir.join("lib");
 
         build.out_dir(&lib_dir);
-        build.compile("zmq");
 
-        // On windows we need to rename the static compiled lib
-        // since its name is unpredictable.
-        if target.contains("msvc")
-            && rename_libzmq_in_dir(&lib_dir, "zmq.lib").is_err()
-        {
-            panic!("unable to find compiled `libzmq` lib");
+        if self.link_static {
+            build.compile("zmq");
+
+            // On windows we need to rename the static compiled lib
+            // since its name is unpredictable.
+            if target.contains("msvc")
+                && rename_libzmq_in_dir(&lib_dir, "zmq.lib").is_err()
+            {
+                panic!("unable to find compiled `libzmq` lib");
+            }
+        } else {
+            compile_shared_zmq(&mut build, &lib_dir, &target, &extra_link_libs);
         }
 
         let source_dir = out_dir.join("source");
@@ -543,11 +1227,15 @@ impl Build {
         dircpy::copy_dir(vendor.join("external"), source_dir.join("external"))
             .expect("unable to copy external dir");
 
-        println!("cargo:rustc-link-search=native={}", lib_dir.display());
-        println!("cargo:rustc-link-lib=static=zmq");
-        println!("cargo:include={}", include_dir.display());
-        println!("cargo:lib={}", lib_dir.display());
-        println!("cargo:out={}", out_dir.display());
+        let zmq_lib =
+            if self.link_static { "static=zmq" } else { "dylib=zmq" };
+
+        Artifacts {
+            include_dir,
+            lib_dir,
+            out_dir,
+            libs: vec![zmq_lib.to_string()],
+        }
     }
 }
 
@@ -17,7 +17,8 @@ fn main() {
         None
     };
 
-    zeromq_src::Build::new()
+    let artifacts = zeromq_src::Build::new()
         .with_libsodium(maybe_libsodium)
         .build();
+    artifacts.print_cargo_metadata();
 }